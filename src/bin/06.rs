@@ -19,11 +19,37 @@
 //! it's helpful that the chunk size can fit entirely in the CPU cache.  Then
 //! `sieve_parallel` also benefits from this as long as there's cache room for
 //! multiple chunks, for the separate jobs in each thread.
+//!
+//! These three pack the sieve into a `Vec<u64>`, one bit per odd candidate,
+//! rather than a `Vec<bool>` - a given chunk then covers 8x the number range
+//! for the same byte budget, which is exactly what the cache-locality story
+//! above cares about.
+//!
+//! There's also `sieve_wheel30`, a "wheel of 30" variant.  The other sieves
+//! are really a "wheel of 2" - only odd numbers are represented, since every
+//! other integer is a multiple of 2.  A wheel of 2*3*5 = 30 goes further,
+//! representing only the 8 residues mod 30 that are coprime to 2, 3, and 5
+//! (1, 7, 11, 13, 17, 19, 23, 29), so it only needs 8 bits per 30 integers
+//! instead of 15.
+//!
+//! `sieve_atkin` takes a different approach entirely: the sieve of Atkin,
+//! which toggles candidates via three quadratic forms before a final pass
+//! strikes out the squares of whatever primes remain, for O(N/log log N)
+//! complexity instead of Eratosthenes' O(N log log N).
+//!
+//! Finally, `Primes` wraps `sieve_range` in an unbounded iterator: a
+//! background thread sieves successive windows and streams the primes it
+//! finds over a channel, so callers never have to commit to a fixed `MAX`
+//! up front.
 
 extern crate itertools;
 extern crate rayon;
 extern crate time;
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
 use itertools::StrideMut;
 use rayon::prelude::*;
 
@@ -35,14 +61,48 @@ const MAX: usize = 1_000_000;
 // https://oeis.org/A006880
 const NUM_PRIMES: usize = 78498;
 
-// For all of these sieves, sieve[i]==true -> 2*i+1 is prime
+// For all of these sieves, bit i of the packed words ==1 -> 2*i+1 is prime
+
+fn get_bit(words: &[u64], i: usize) -> bool {
+    words[i >> 6] & (1 << (i & 63)) != 0
+}
+
+fn clear_bit(words: &mut [u64], i: usize) {
+    words[i >> 6] &= !(1u64 << (i & 63));
+}
+
+/// Count the set bits among the first `len` bits of `words`.
+fn count_bits(words: &[u64], len: usize) -> usize {
+    let full_words = len / 64;
+    let mut count: usize = words[..full_words].iter().map(|w| w.count_ones() as usize).sum();
+    let rem = len % 64;
+    if rem > 0 {
+        let mask = (1u64 << rem) - 1;
+        count += (words[full_words] & mask).count_ones() as usize;
+    }
+    count
+}
+
+/// sqrt(max), rounded up to a multiple of 128 candidates (64 bits) of slack.
+///
+/// `sieve_serial` packs its result into whole `u64` words, so a bit length that isn't a
+/// multiple of 64 leaves a few trailing bits in the last word unaccounted for by the primes
+/// that produced it. Rounding here keeps the low/high split word-aligned, so `low` below is
+/// never more than exactly the bits `sieve_serial` actually proved correct.
+fn small_max_aligned(max: usize) -> usize {
+    let sqrt_max = (max as f64).sqrt().ceil() as usize;
+    (sqrt_max / 128 + 1) * 128
+}
 
 /// Sieve odd integers for primes < max.
-fn sieve_serial(max: usize) -> Vec<bool> {
-    let mut sieve = vec![true; max / 2];
-    sieve[0] = false; // 1 is not prime
+fn sieve_serial(max: usize) -> Vec<u64> {
+    // floored at 1 word: max < 2 needs no real bits, but clear_bit below always touches bit 0,
+    // and `max == 1` is squarely inside this function's contract, not an out-of-domain input
+    let words = std::cmp::max((max / 2).div_ceil(64), 1);
+    let mut sieve = vec![!0u64; words];
+    clear_bit(&mut sieve, 0); // 1 is not prime
     for i in 1 .. {
-        if sieve[i] {
+        if get_bit(&sieve, i) {
             let p = 2 * i + 1;
             let pp = p * p;
             if pp >= max { break }
@@ -52,18 +112,31 @@ fn sieve_serial(max: usize) -> Vec<bool> {
     sieve
 }
 
-/// Sieve odd integers for primes < max using chunks.
-fn sieve_chunks(max: usize) -> Vec<bool> {
-    // first compute the small primes, up to sqrt(max).
-    let small_max = (max as f64).sqrt().ceil() as usize;
+// chunk_size (candidates) in whole 64-bit words, so a chunk's bit range never shares a word
+// with its neighbor.
+fn chunk_words(chunk_size: usize) -> usize {
+    chunk_size.div_ceil(64)
+}
+
+/// Sieve odd integers for primes < max using chunks of `CHUNK_SIZE` candidates.
+fn sieve_chunks(max: usize) -> Vec<u64> {
+    sieve_chunks_sized(max, CHUNK_SIZE)
+}
+
+/// Sieve odd integers for primes < max using chunks of `chunk_size` candidates.
+fn sieve_chunks_sized(max: usize, chunk_size: usize) -> Vec<u64> {
+    // first compute the small primes, up to sqrt(max), rounded so the low/high
+    // split below falls on a word boundary (no chunk straddles two words' bits).
+    let small_max = small_max_aligned(max);
     let mut sieve = sieve_serial(small_max);
-    sieve.resize(max / 2, true);
+    let low_words = sieve.len();
+    sieve.resize((max / 2).div_ceil(64), !0u64);
 
+    let chunk_words = chunk_words(chunk_size);
     {
-        let (low, high) = sieve.split_at_mut(small_max / 2);
-        for (chunk_index, chunk) in high.chunks_mut(CHUNK_SIZE).enumerate() {
-            let i = small_max / 2 + chunk_index * CHUNK_SIZE;
-            let base = i * 2 + 1;
+        let (low, high) = sieve.split_at_mut(low_words);
+        for (chunk_index, chunk) in high.chunks_mut(chunk_words).enumerate() {
+            let base = (low_words + chunk_index * chunk_words) * 64 * 2 + 1;
             update_chunk(low, chunk, base);
         }
     }
@@ -71,21 +144,28 @@ fn sieve_chunks(max: usize) -> Vec<bool> {
     sieve
 }
 
-/// Sieve odd integers for primes < max, in parallel!
-fn sieve_parallel(max: usize) -> Vec<bool> {
-    // first compute the small primes, up to sqrt(max).
-    let small_max = (max as f64).sqrt().ceil() as usize;
+/// Sieve odd integers for primes < max, in parallel, using chunks of `CHUNK_SIZE` candidates.
+fn sieve_parallel(max: usize) -> Vec<u64> {
+    sieve_parallel_sized(max, CHUNK_SIZE)
+}
+
+/// Sieve odd integers for primes < max, in parallel, using chunks of `chunk_size` candidates.
+fn sieve_parallel_sized(max: usize, chunk_size: usize) -> Vec<u64> {
+    // first compute the small primes, up to sqrt(max), rounded so the low/high
+    // split below falls on a word boundary (no chunk straddles two words' bits).
+    let small_max = small_max_aligned(max);
     let mut sieve = sieve_serial(small_max);
-    sieve.resize(max / 2, true);
+    let low_words = sieve.len();
+    sieve.resize((max / 2).div_ceil(64), !0u64);
 
+    let chunk_words = chunk_words(chunk_size);
     {
-        let (low, high) = sieve.split_at_mut(small_max / 2);
-        high.par_chunks_mut(CHUNK_SIZE)
+        let (low, high) = sieve.split_at_mut(low_words);
+        high.par_chunks_mut(chunk_words)
             .enumerate() // to figure out where this chunk came from
             .weight_max() // ensure every single chunk is a separate rayon job
             .for_each(|(chunk_index, chunk)| {
-                let i = small_max / 2 + chunk_index * CHUNK_SIZE;
-                let base = i * 2 + 1;
+                let base = (low_words + chunk_index * chunk_words) * 64 * 2 + 1;
                 update_chunk(low, chunk, base);
             });
     }
@@ -94,17 +174,17 @@ fn sieve_parallel(max: usize) -> Vec<bool> {
 }
 
 /// Update a chunk with low primes
-fn update_chunk(low: &[bool], chunk: &mut [bool], base: usize) {
-    let max = base + chunk.len() * 2;
-    for (i, &is_prime) in low.iter().enumerate() {
-        if is_prime {
+fn update_chunk(low: &[u64], chunk: &mut [u64], base: usize) {
+    let max = base + chunk.len() * 64 * 2;
+    for i in 0 .. low.len() * 64 {
+        if get_bit(low, i) {
             let p = 2 * i + 1;
             let pp = p * p;
             if pp >= max { break }
 
             let pm = if pp < base {
                 // p² is too small - find the first odd multiple that's in range
-                ((base + p - 1) / p | 1) * p
+                (base.div_ceil(p) | 1) * p
             } else { pp };
 
             if pm < max {
@@ -114,25 +194,341 @@ fn update_chunk(low: &[bool], chunk: &mut [bool], base: usize) {
     }
 }
 
-fn clear_stride(slice: &mut [bool], from: usize, stride: usize) {
+/// Clear every `stride`'th bit starting at `from`, advancing a word at a time.
+fn clear_stride(words: &mut [u64], from: usize, stride: usize) {
+    let bits = words.len() * 64;
+    let mut i = from;
+    while i < bits {
+        clear_bit(words, i);
+        i += stride;
+    }
+}
+
+/// Sieve the half-open window [lo, hi) for primes, without materializing anything below lo.
+///
+/// Unlike the other sieves here, this isn't anchored at 0 - it only needs the small primes up
+/// to sqrt(hi), so it can reach arbitrarily high windows (e.g. primes near 10^18) that a
+/// `MAX`-sized `Vec<bool>` could never hold. Each window is independent of the others, which
+/// is exactly the property the concurrent sieve relies on to parallelize and distribute.
+pub fn sieve_range(lo: u64, hi: u64) -> Vec<u64> {
+    assert!(lo <= hi);
+    if lo == hi {
+        return Vec::new();
+    }
+
+    // small primes up to sqrt(hi), the only state shared across windows
+    let small_max = (hi as f64).sqrt().ceil() as usize;
+    let small_sieve = sieve_serial(small_max);
+    sieve_window(lo, hi, &small_sieve, small_max)
+}
+
+/// The shared worker behind `sieve_range` and `Primes`: sieve `[lo, hi)` using an already-computed
+/// sieve of odd primes below `small_max`, which the caller must ensure covers at least sqrt(hi).
+fn sieve_window(lo: u64, hi: u64, small_sieve: &[u64], small_max: usize) -> Vec<u64> {
+    let mut primes = Vec::new();
+    if lo <= 2 && 2 < hi {
+        primes.push(2);
+    }
+
+    // first odd number >= lo that isn't 1 (which the window doesn't treat as composite)
+    let base = if lo.is_multiple_of(2) { lo + 1 } else { lo };
+    let base = std::cmp::max(base, 3);
+    if base >= hi {
+        return primes;
+    }
+
+    let mut window = vec![true; ((hi - base) / 2 + 1) as usize];
+
+    // bounded to small_max/2, not small_sieve.len()*64 - the trailing bits of the last
+    // word weren't proved correct by sieve_serial's own primes-below-sqrt(small_max) pass
+    for i in 0 .. small_max / 2 {
+        if !get_bit(small_sieve, i) { continue }
+        let p = 2 * i as u64 + 1;
+        let pp = p * p;
+        if pp >= hi { break }
+
+        let from = std::cmp::max(pp, base);
+        let start = (from.div_ceil(p) | 1) * p;
+        if start >= hi { continue }
+
+        clear_stride_u64(&mut window, ((start - base) / 2) as usize, p);
+    }
+
+    primes.extend(window.iter().enumerate()
+        .filter(|&(_, &is_prime)| is_prime)
+        .map(|(i, _)| base + 2 * i as u64));
+    primes
+}
+
+fn clear_stride_u64(slice: &mut [bool], from: usize, stride: u64) {
     let slice = &mut slice[from..];
     for x in StrideMut::from_slice(slice, stride as isize) {
         *x = false;
     }
 }
 
-fn measure(f: fn(usize) -> Vec<bool>) -> u64 {
+// How far the producer thread in `Primes` may get ahead of the consumer.
+const PRIME_BUFFER: usize = 1024;
+
+/// An unbounded iterator over the primes, sieved lazily in the background.
+///
+/// A producer thread walks successive `sieve_range` windows and streams each prime it finds
+/// over a bounded channel, so memory stays bounded to one window plus `PRIME_BUFFER` no matter
+/// how many primes are requested - usable as `Primes::new().take_while(|&p| p < n)`. The small
+/// prime sieve each window needs is cached and only regrown (geometrically) as `hi` outgrows it,
+/// so its cost amortizes across windows instead of being paid again on every single one.
+pub struct Primes {
+    rx: mpsc::Receiver<u64>,
+}
+
+impl Primes {
+    /// Start streaming primes, sieving `CHUNK_SIZE` numbers per window.
+    pub fn new() -> Primes {
+        Primes::with_window(CHUNK_SIZE as u64)
+    }
+
+    /// Start streaming primes, sieving `window` numbers per window. `window` can be as small as
+    /// 1 - the background thread's small-prime sieve handles a tiny first window correctly.
+    pub fn with_window(window: u64) -> Primes {
+        assert!(window >= 1);
+        let (tx, rx) = mpsc::sync_channel(PRIME_BUFFER);
+        thread::spawn(move || {
+            let mut lo = 0;
+            let mut small_max = 0;
+            let mut small_sieve = Vec::new();
+            loop {
+                let hi = lo + window;
+
+                let needed = (hi as f64).sqrt().ceil() as usize;
+                if needed > small_max {
+                    // grow generously rather than to the exact need, so the next several
+                    // windows can reuse this sieve instead of triggering another regrow
+                    small_max = std::cmp::max(needed, small_max * 2);
+                    small_sieve = sieve_serial(small_max);
+                }
+
+                for p in sieve_window(lo, hi, &small_sieve, small_max) {
+                    if tx.send(p).is_err() { return }
+                }
+                lo = hi;
+            }
+        });
+        Primes { rx }
+    }
+}
+
+impl Default for Primes {
+    fn default() -> Primes {
+        Primes::new()
+    }
+}
+
+impl Iterator for Primes {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        self.rx.recv().ok()
+    }
+}
+
+// For sieve_atkin, sieve[n]==true -> n is prime (every integer gets a slot, unlike the wheels)
+
+/// Sieve integers for primes < max using the sieve of Atkin.
+fn sieve_atkin(max: usize) -> Vec<bool> {
+    let limit = (max as f64).sqrt().ceil() as usize;
+    let sieve: Vec<AtomicBool> = (0 .. max).map(|_| AtomicBool::new(false)).collect();
+
+    // the toggle loops partition over x: every x is visited exactly once across the whole
+    // sieve, so the total work is O(limit²) == O(max), not the O(max²/CHUNK_SIZE) that resieving
+    // the full (x, y) range from scratch per output chunk used to cost. Different x can toggle
+    // the same index, so workers write straight into the shared sieve via `fetch_xor` rather
+    // than collecting into a per-x Vec and merging it in afterwards.
+    (1 .. limit + 1).into_par_iter()
+        .weight_max()
+        .for_each(|x| toggle_atkin_x(&sieve, x, max, limit));
+
+    let mut sieve: Vec<bool> = sieve.into_iter().map(AtomicBool::into_inner).collect();
+
+    if max > 2 { sieve[2] = true }
+    if max > 3 { sieve[3] = true }
+    if max > 5 { sieve[5] = true }
+
+    // strike out the squares of everything still marked prime
+    for n in 5 .. limit {
+        if sieve[n] {
+            let nn = n * n;
+            let mut k = nn;
+            while k < max {
+                sieve[k] = false;
+                k += nn;
+            }
+        }
+    }
+
+    sieve
+}
+
+/// Toggle every index in `[0, max)` that the three Atkin quadratic forms hit for this one `x`.
+fn toggle_atkin_x(sieve: &[AtomicBool], x: usize, max: usize, limit: usize) {
+    let xx4 = 4 * x * x;
+    let xx3 = 3 * x * x;
+
+    for y in 1 ..= limit {
+        let yy = y * y;
+
+        let n = xx4 + yy;
+        if n < max && (n % 12 == 1 || n % 12 == 5) {
+            sieve[n].fetch_xor(true, Ordering::Relaxed);
+        }
+
+        let n = xx3 + yy;
+        if n < max && n % 12 == 7 {
+            sieve[n].fetch_xor(true, Ordering::Relaxed);
+        }
+
+        if x > y {
+            let n = xx3 - yy;
+            if n < max && n % 12 == 11 {
+                sieve[n].fetch_xor(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// The eight residues mod 30 that are coprime to 2, 3, and 5.
+const WHEEL: [usize; 8] = [1, 7, 11, 13, 17, 19, 23, 29];
+
+// The gaps between consecutive entries of WHEEL, cycling around (29 -> 31).
+const WHEEL_GAP: [usize; 8] = [6, 4, 2, 4, 2, 4, 6, 2];
+
+// RESIDUE_POS[n % 30] gives n's slot (0..8) in WHEEL, or 8 if n isn't coprime to 30.
+const RESIDUE_POS: [u8; 30] = [
+    8, 0, 8, 8, 8, 8, 8, 1, 8, 8, 8, 2, 8, 3, 8, 8, 8, 4, 8, 5, 8, 8, 8, 6, 8, 8, 8, 8, 8, 7,
+];
+
+// For the wheel sieve, sieve[i]==true -> wheel_value(i) is prime (2, 3, 5 are implicit).
+
+fn wheel_index(n: usize) -> usize {
+    RESIDUE_POS[n % 30] as usize + (n / 30) * 8
+}
+
+fn wheel_value(i: usize) -> usize {
+    WHEEL[i % 8] + (i / 8) * 30
+}
+
+/// Sieve integers coprime to 30 for primes < max, using a wheel of 2*3*5.
+fn sieve_wheel30(max: usize) -> Vec<bool> {
+    let mut sieve = vec![true; (max / 30 + 1) * 8];
+    sieve[0] = false; // 1 is not prime
+
+    for i in 0..sieve.len() {
+        if sieve[i] {
+            let p = wheel_value(i);
+            let pp = p * p;
+            if pp >= max { break }
+            clear_wheel_stride(&mut sieve, p, max);
+        }
+    }
+
+    sieve
+}
+
+/// Clear multiples of prime `p` (p > 5) from a wheel-30 sieve, starting at p².
+///
+/// Since `p` is coprime to 30, so is every wheel value `m`, and so is their
+/// product - so walking `m` across the eight wheel spokes (via `WHEEL_GAP`)
+/// visits exactly the multiples of `p` that the wheel sieve represents.
+///
+/// `wheel_index`'s jump from one spoke to the next depends only on `p` and the spoke, not on
+/// which lap of the wheel `m` is currently on - `p` and `m` are both coprime to 30, so `n = p*m`
+/// mod 30 (and hence the div/mod `wheel_index` would otherwise redo) repeats with period 8.
+/// Precomputing all eight jumps once turns the per-composite cost into a table lookup and an
+/// add, the same way `clear_stride` strides through the index domain directly.
+fn clear_wheel_stride(sieve: &mut [bool], p: usize, max: usize) {
+    let mut idx_gap = [0usize; 8];
+    for pos in 0..8 {
+        let idx0 = wheel_index(p * WHEEL[pos]);
+        let idx1 = wheel_index(p * (WHEEL[pos] + WHEEL_GAP[pos]));
+        idx_gap[pos] = idx1 - idx0;
+    }
+
+    let mut n = p * p;
+    let mut idx = wheel_index(n);
+    let mut pos = RESIDUE_POS[p % 30] as usize;
+    loop {
+        if n >= max { break }
+        sieve[idx] = false;
+        n += p * WHEEL_GAP[pos];
+        idx += idx_gap[pos];
+        pos = (pos + 1) % 8;
+    }
+}
+
+fn measure(f: fn(usize) -> Vec<u64>) -> u64 {
     let start = time::precise_time_ns();
     let sieve = f(MAX);
     let duration = time::precise_time_ns() - start;
 
     // sanity check the number of primes found
-    let num_primes = 1 + sieve.into_iter().filter(|&b| b).count();
+    let num_primes = 1 + count_bits(&sieve, MAX / 2);
     assert_eq!(num_primes, NUM_PRIMES);
 
     duration
 }
 
+fn measure_wheel30() -> u64 {
+    let start = time::precise_time_ns();
+    let sieve = sieve_wheel30(MAX);
+    let duration = time::precise_time_ns() - start;
+
+    // sanity check the number of primes found; 2, 3, 5 are implicit
+    let num_primes = 3 + sieve.iter().enumerate()
+        .filter(|&(i, &is_prime)| is_prime && wheel_value(i) < MAX)
+        .count();
+    assert_eq!(num_primes, NUM_PRIMES);
+
+    duration
+}
+
+fn measure_atkin() -> u64 {
+    let start = time::precise_time_ns();
+    let sieve = sieve_atkin(MAX);
+    let duration = time::precise_time_ns() - start;
+
+    // sanity check against the same prime count the Eratosthenes variants find
+    let num_primes = sieve.into_iter().filter(|&b| b).count();
+    assert_eq!(num_primes, NUM_PRIMES);
+
+    duration
+}
+
+fn measure_chunk_size(chunk_size: usize) -> u64 {
+    let start = time::precise_time_ns();
+    let sieve = sieve_parallel_sized(MAX, chunk_size);
+    let duration = time::precise_time_ns() - start;
+
+    let num_primes = 1 + count_bits(&sieve, MAX / 2);
+    assert_eq!(num_primes, NUM_PRIMES);
+
+    duration
+}
+
+/// Sweep `chunk_size` over a geometric range, from 2^10 candidates up to a few MB worth,
+/// timing `sieve_parallel` at each to find the crossover where chunks stop fitting in cache.
+fn sweep_chunk_sizes() {
+    println!("\nsweeping chunk_size (parallel):");
+
+    let mut baseline = None;
+    let mut chunk_size = 1 << 10;
+    while chunk_size <= 1 << 23 {
+        let duration = measure_chunk_size(chunk_size);
+        let baseline = *baseline.get_or_insert(duration);
+        println!("  {:9}: {:10} ns -> {:.2}x speedup", chunk_size, duration,
+                 baseline as f64 / duration as f64);
+        chunk_size *= 2;
+    }
+}
+
 fn main() {
     rayon::initialize(rayon::Configuration::new()).unwrap();
 
@@ -146,5 +542,29 @@ fn main() {
     let parallel = measure(sieve_parallel);
     println!("parallel: {:10} ns -> {:.2}x speedup", parallel,
              chunks as f64 / parallel as f64);
+
+    let wheel30 = measure_wheel30();
+    println!(" wheel30: {:10} ns -> {:.2}x speedup", wheel30,
+             serial as f64 / wheel30 as f64);
+
+    let atkin = measure_atkin();
+    let atkin_speedup = serial as f64 / atkin as f64;
+    if atkin_speedup >= 1.0 {
+        println!("   atkin: {:10} ns -> {:.2}x speedup", atkin, atkin_speedup);
+    } else {
+        println!("   atkin: {:10} ns -> {:.2}x of serial (not competitive yet)",
+                 atkin, atkin_speedup);
+    }
+
+    // demonstrate reaching windows no MAX-anchored sieve above could touch
+    let hi18 = 1_000_000_000_000_000_000u64;
+    let window = sieve_range(hi18, hi18 + 100_000);
+    println!("   range: {} primes found in [{}, {})", window.len(), hi18, hi18 + 100_000);
+
+    // demonstrate streaming primes without committing to a fixed MAX up front
+    let streamed: Vec<u64> = Primes::new().take_while(|&p| p < 100).collect();
+    println!("    iter: {} primes found below 100", streamed.len());
+
+    sweep_chunk_sizes();
 }
 